@@ -3,18 +3,64 @@ use std::{
     mem,
     os::raw::{c_char, c_int, c_void},
     ptr::NonNull,
+    sync::Arc,
+    time::Duration,
 };
 
-unsafe fn property_callback(payload: *mut String, _name: *const c_char, value: *const c_char, _serial: u32) {
-    let cvalue = CStr::from_ptr(value);
-    (*payload) = cvalue.to_str().unwrap().to_string();
-}
+type Callback = unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char, u32);
 
-type Callback = unsafe fn(*mut String, *const c_char, *const c_char, u32);
+unsafe extern "C" fn property_callback(payload: *mut c_void, _name: *const c_char, value: *const c_char, _serial: u32) {
+    let payload = &mut *(payload as *mut Vec<u8>);
+    *payload = CStr::from_ptr(value).to_bytes().to_vec();
+}
 
 type SystemPropertyGetFn = unsafe extern "C" fn(*const c_char, *mut c_char) -> c_int;
 type SystemPropertyFindFn = unsafe extern "C" fn(*const c_char) -> *const c_void;
-type SystemPropertyReadCallbackFn = unsafe extern "C" fn(*const c_void, Callback, *mut String) -> *const c_void;
+type SystemPropertyReadCallbackFn = unsafe extern "C" fn(*const c_void, Callback, *mut c_void) -> *const c_void;
+type PropInfoCallback = unsafe extern "C" fn(*const c_void, *mut c_void);
+type SystemPropertyForeachFn = unsafe extern "C" fn(PropInfoCallback, *mut c_void) -> c_int;
+type SystemPropertySetFn = unsafe extern "C" fn(*const c_char, *const c_char) -> c_int;
+type SystemPropertyWaitFn =
+    unsafe extern "C" fn(*const c_void, u32, *mut u32, *const libc::timespec) -> bool;
+
+unsafe extern "C" fn property_value_serial_callback(
+    payload: *mut c_void,
+    _name: *const c_char,
+    value: *const c_char,
+    serial: u32,
+) {
+    let payload = &mut *(payload as *mut (Option<String>, u32));
+    payload.0 = CStr::from_ptr(value).to_str().ok().map(str::to_string);
+    payload.1 = serial;
+}
+
+unsafe extern "C" fn property_name_value_callback(
+    payload: *mut c_void,
+    name: *const c_char,
+    value: *const c_char,
+    _serial: u32,
+) {
+    let payload = &mut *(payload as *mut (Option<String>, Option<String>));
+    payload.0 = CStr::from_ptr(name).to_str().ok().map(str::to_string);
+    payload.1 = CStr::from_ptr(value).to_str().ok().map(str::to_string);
+}
+
+struct ForeachCookie<'a> {
+    read_callback_fn: SystemPropertyReadCallbackFn,
+    callback: &'a mut dyn FnMut(&str, &str),
+}
+
+// Properties whose name or value is not valid UTF-8 are skipped, same as the non-UTF-8
+// handling `Implementation::get` does via `PropertyError::InvalidUtf8`, except `foreach`
+// has no error channel to report them through.
+unsafe extern "C" fn foreach_trampoline(info: *const c_void, cookie: *mut c_void) {
+    let cookie = &mut *(cookie as *mut ForeachCookie);
+    let mut name_value: (Option<String>, Option<String>) = (None, None);
+    (cookie.read_callback_fn)(info, property_name_value_callback, (&mut name_value as *mut (Option<String>, Option<String>)).cast());
+    if let (Some(name), Some(value)) = name_value {
+        (cookie.callback)(&name, &value);
+    }
+}
 
 #[derive(Debug)]
 struct LibC(NonNull<c_void>);
@@ -44,9 +90,13 @@ enum Implementation {
     New {
         find_fn: SystemPropertyFindFn,
         read_callback_fn: SystemPropertyReadCallbackFn,
+        foreach_fn: SystemPropertyForeachFn,
+        set_fn: Option<SystemPropertySetFn>,
+        wait_fn: Option<SystemPropertyWaitFn>,
     },
     Old {
         get_fn: SystemPropertyGetFn,
+        set_fn: Option<SystemPropertySetFn>,
     }
 }
 
@@ -61,16 +111,24 @@ impl Implementation {
     unsafe fn load_new(libc_so: *mut c_void) -> Option<Implementation> {
         let read_callback_fn = load_fn(libc_so, b"__system_property_read_callback\0")?;
         let find_fn = load_fn(libc_so, b"__system_property_find\0")?;
+        let foreach_fn = load_fn(libc_so, b"__system_property_foreach\0")?;
+        let set_fn = load_fn(libc_so, b"__system_property_set\0").map(|f| mem::transmute(f));
+        let wait_fn = load_fn(libc_so, b"__system_property_wait\0").map(|f| mem::transmute(f));
         Some(Implementation::New {
             find_fn: mem::transmute(find_fn),
             read_callback_fn: mem::transmute(read_callback_fn),
+            foreach_fn: mem::transmute(foreach_fn),
+            set_fn,
+            wait_fn,
         })
     }
 
     unsafe fn load_old(libc_so: *mut c_void) -> Option<Implementation> {
         let get_fn = load_fn(libc_so, b"__system_property_get\0")?;
+        let set_fn = load_fn(libc_so, b"__system_property_set\0").map(|f| mem::transmute(f));
         Some(Implementation::Old {
             get_fn: mem::transmute(get_fn),
+            set_fn,
         })
     }
 
@@ -79,22 +137,22 @@ impl Implementation {
             .or_else(|| Self::load_old(libc_so))
     }
 
-    fn get(&self, cname: *const c_char) -> Option<String> {
+    fn get(&self, cname: *const c_char) -> Result<String, crate::PropertyError> {
         match self {
-            Implementation::New { find_fn, read_callback_fn } => {
+            Implementation::New { find_fn, read_callback_fn, .. } => {
                 let info = unsafe { (find_fn)(cname) };
 
                 if info.is_null() {
-                    return None;
+                    return Err(crate::PropertyError::PropertyAbsent);
                 }
 
-                let mut result = String::new();
+                let mut raw: Vec<u8> = Vec::new();
 
-                unsafe { (read_callback_fn)(info, property_callback, &mut result) };
+                unsafe { (read_callback_fn)(info, property_callback, (&mut raw as *mut Vec<u8>).cast()) };
 
-                Some(result)
+                String::from_utf8(raw).map_err(|_| crate::PropertyError::InvalidUtf8)
             }
-            Implementation::Old { get_fn } => {
+            Implementation::Old { get_fn, .. } => {
                 // The constant is PROP_VALUE_MAX in Android's libc/include/sys/system_properties.h
                 const PROPERTY_VALUE_MAX: usize = 92;
                 let mut buffer: Vec<u8> = Vec::with_capacity(PROPERTY_VALUE_MAX);
@@ -105,18 +163,126 @@ impl Implementation {
                 if len > 0 {
                     assert!(len as usize <= buffer.capacity());
                     unsafe { buffer.set_len(len as usize); }
-                    String::from_utf8(buffer).ok()
+                    String::from_utf8(buffer).map_err(|_| crate::PropertyError::InvalidUtf8)
                 } else {
-                    None
+                    Err(crate::PropertyError::PropertyAbsent)
                 }
             }
         }
     }
+
+    /// Calls `f` once for every property this process is allowed to read.
+    ///
+    /// Does nothing on the pre-L `Old` implementation, which has no foreach symbol.
+    /// Properties whose name or value is not valid UTF-8 are skipped.
+    fn foreach(&self, f: &mut dyn FnMut(&str, &str)) {
+        if let Implementation::New { read_callback_fn, foreach_fn, .. } = self {
+            let mut cookie = ForeachCookie {
+                read_callback_fn: *read_callback_fn,
+                callback: f,
+            };
+
+            unsafe {
+                (foreach_fn)(foreach_trampoline, (&mut cookie as *mut ForeachCookie).cast());
+            }
+        }
+    }
+
+    fn set(&self, cname: *const c_char, cvalue: *const c_char) -> Result<(), crate::SetError> {
+        let set_fn = match self {
+            Implementation::New { set_fn, .. } | Implementation::Old { set_fn, .. } => {
+                set_fn.ok_or(crate::SetError::Unsupported)?
+            }
+        };
+
+        let result = unsafe { (set_fn)(cname, cvalue) };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(crate::SetError::SetFailed)
+        }
+    }
+
+    /// Returns `None` on the pre-L `Old` implementation, which has no serials to wait on.
+    fn watch(&self, cname: *const c_char, libc_so: Arc<LibC>) -> Option<PropertyWatcher> {
+        let (find_fn, read_callback_fn, wait_fn) = match self {
+            Implementation::New { find_fn, read_callback_fn, wait_fn: Some(wait_fn), .. } => {
+                (*find_fn, *read_callback_fn, *wait_fn)
+            }
+            _ => return None,
+        };
+
+        let info = unsafe { (find_fn)(cname) };
+        if info.is_null() {
+            return None;
+        }
+
+        let mut value_serial: (Option<String>, u32) = (None, 0);
+        unsafe { (read_callback_fn)(info, property_value_serial_callback, (&mut value_serial as *mut (Option<String>, u32)).cast()) };
+
+        Some(PropertyWatcher {
+            read_callback_fn,
+            wait_fn,
+            info,
+            serial: value_serial.1,
+            libc_so,
+        })
+    }
+}
+
+/// A handle on a single property, used to block until it changes.
+///
+/// Returned by [`Properties::watch`]. Not available on the pre-L `Old` implementation,
+/// which predates `__system_property_wait` and per-property serials.
+#[derive(Debug)]
+pub(crate) struct PropertyWatcher {
+    read_callback_fn: SystemPropertyReadCallbackFn,
+    wait_fn: SystemPropertyWaitFn,
+    info: *const c_void,
+    serial: u32,
+    // Keeps the `libc.so` handle `read_callback_fn`/`wait_fn` were resolved from alive for as
+    // long as this watcher can still call them; dropping the owning `Properties` must not
+    // `dlclose` out from under an in-flight `wait`.
+    #[allow(unused)]
+    libc_so: Arc<LibC>,
+}
+
+// `info` points into Android's property shared memory, which lives for the lifetime of the
+// process and is safe to read from any thread, same as `LibC` above.
+unsafe impl Send for PropertyWatcher {}
+unsafe impl Sync for PropertyWatcher {}
+
+impl PropertyWatcher {
+    /// Blocks until the property changes (or `timeout` elapses, if given), then returns its
+    /// new value and serial. Returns `None` on timeout, if the underlying wait call fails,
+    /// or if the new value is not valid UTF-8.
+    pub(crate) fn wait(&mut self, timeout: Option<Duration>) -> Option<(String, u32)> {
+        let timespec = timeout.map(|d| libc::timespec {
+            tv_sec: d.as_secs() as libc::time_t,
+            tv_nsec: d.subsec_nanos() as libc::c_long,
+        });
+        let timespec_ptr = timespec
+            .as_ref()
+            .map_or(std::ptr::null(), |t| t as *const libc::timespec);
+
+        let mut new_serial: u32 = 0;
+        let changed = unsafe { (self.wait_fn)(self.info, self.serial, &mut new_serial, timespec_ptr) };
+        if !changed {
+            return None;
+        }
+
+        let mut value_serial: (Option<String>, u32) = (None, 0);
+        unsafe { (self.read_callback_fn)(self.info, property_value_serial_callback, (&mut value_serial as *mut (Option<String>, u32)).cast()) };
+
+        self.serial = value_serial.1;
+        let (value, serial) = value_serial;
+        value.map(|value| (value, serial))
+    }
 }
 
 #[derive(Debug)]
 pub struct Properties {
-    #[allow(unused)] libc_so: LibC,
+    #[allow(unused)] libc_so: Arc<LibC>,
     implementation: Implementation,
 }
 
@@ -125,11 +291,29 @@ impl Properties {
     pub(crate) fn new() -> Option<Self> {
         let mut libc_so = LibC::new()?;
         let implementation = unsafe { Implementation::new(libc_so.as_mut())? };
-        Some(Self { libc_so, implementation })
+        Some(Self { libc_so: Arc::new(libc_so), implementation })
     }
 
-    pub(crate) fn get(&self, name: &str) -> Option<String> {
-        let cname = CString::new(name).ok()?;
+    pub(crate) fn get(&self, name: &str) -> Result<String, crate::PropertyError> {
+        let cname = CString::new(name).map_err(|_| crate::PropertyError::InvalidName)?;
         self.implementation.get(cname.as_ptr().cast())
     }
+
+    /// Calls `f` for every property this process is allowed to read.
+    pub(crate) fn foreach<F: FnMut(&str, &str)>(&self, mut f: F) {
+        self.implementation.foreach(&mut f);
+    }
+
+    pub(crate) fn set(&self, name: &str, value: &str) -> Result<(), crate::SetError> {
+        let cname = CString::new(name).map_err(|_| crate::SetError::InvalidName)?;
+        let cvalue = CString::new(value).map_err(|_| crate::SetError::InvalidValue)?;
+        self.implementation.set(cname.as_ptr().cast(), cvalue.as_ptr().cast())
+    }
+
+    /// Returns a watcher on `name`, or `None` if the property does not exist or the
+    /// implementation has no support for waiting on serials.
+    pub(crate) fn watch(&self, name: &str) -> Option<PropertyWatcher> {
+        let cname = CString::new(name).ok()?;
+        self.implementation.watch(cname.as_ptr().cast(), Arc::clone(&self.libc_so))
+    }
 }
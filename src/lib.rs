@@ -20,9 +20,11 @@
 //!
 //! ## Listing and setting properties
 //!
-//! For the sake of simplicity this crate currently only contains what's needed by wgpu.
-//! The implementations for listing and setting properties can be added back if anyone needs
-//! them (let me know by filing an issue).
+//! All properties the process can read can be enumerated with [`AndroidSystemProperties::foreach`],
+//! and properties can be set with [`AndroidSystemProperties::set`]. Values can be parsed directly
+//! with [`AndroidSystemProperties::get_bool`] and [`AndroidSystemProperties::get_parsed`], and
+//! [`AndroidSystemProperties::watch`] returns a [`PropertyWatcher`] that blocks until a property
+//! changes.
 //!
 //! ## License
 //!
@@ -36,107 +38,75 @@
 //! [LICENSE-APACHE]: https://github.com/nical/android_system_properties/blob/804681c5c1c93d4fab29c1a2f47b7d808dc70fd3/LICENSE-APACHE
 //! [LICENSE-MIT]: https://github.com/nical/android_system_properties/blob/804681c5c1c93d4fab29c1a2f47b7d808dc70fd3/LICENSE-MIT
 
-#[cfg(target_os = "android")]
-use std::{
-    ffi::{CStr, CString},
-    mem,
-    os::raw::{c_char, c_int, c_void},
-};
-
-#[cfg(target_os = "android")]
-unsafe fn property_callback(payload: *mut String, _name: *const c_char, value: *const c_char, _serial: u32) {
-    let cvalue = CStr::from_ptr(value);
-    (*payload) = cvalue.to_str().unwrap().to_string();
-}
+use std::{str::FromStr, time::Duration};
 
 #[cfg(target_os = "android")]
-type Callback = unsafe fn(*mut String, *const c_char, *const c_char, u32);
+mod android;
 
 #[cfg(target_os = "android")]
-type SystemPropertyGetFn = unsafe extern "C" fn(*const c_char, *mut c_char) -> c_int;
-#[cfg(target_os = "android")]
-type SystemPropertyFindFn = unsafe extern "C" fn(*const c_char) -> *const c_void;
-#[cfg(target_os = "android")]
-type SystemPropertyReadCallbackFn = unsafe extern "C" fn(*const c_void, Callback, *mut String) -> *const c_void;
+use android::Properties;
 
-#[cfg(target_os = "android")]
-#[derive(Debug)]
-enum Implementation {
-    New {
-        find_fn: SystemPropertyFindFn,
-        read_callback_fn: SystemPropertyReadCallbackFn,
-    },
-    Old {
-        get_fn: SystemPropertyGetFn,
-    }
-}
+// PROP_NAME_MAX and PROP_VALUE_MAX in Android's libc/include/sys/system_properties.h,
+// including the NUL terminator.
+const PROP_NAME_MAX: usize = 32;
+const PROP_VALUE_MAX: usize = 92;
 
-#[cfg(target_os = "android")]
-unsafe fn load_fn(libc_so: *mut c_void, cname: &[u8]) -> Option<*const c_void> {
-    match libc::dlsym(libc_so, cname.as_ptr().cast()) {
-        func if !func.is_null() => Some(func),
-        _ => None,
-    }
+/// An error returned by [`AndroidSystemProperties::set`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetError {
+    /// Setting properties is not supported on this platform, or `libc.so` could not be
+    /// loaded, or it does not expose `__system_property_set`.
+    Unsupported,
+    /// `name` contains a NUL byte or is `PROP_NAME_MAX` bytes or longer (the limit
+    /// includes the NUL terminator).
+    InvalidName,
+    /// `value` contains a NUL byte or is `PROP_VALUE_MAX` bytes or longer (the limit
+    /// includes the NUL terminator).
+    InvalidValue,
+    /// The underlying `__system_property_set` call reported failure.
+    SetFailed,
 }
 
-#[cfg(target_os = "android")]
-impl Implementation {
-    unsafe fn load_new(libc_so: *mut c_void) -> Option<Implementation> {
-        let read_callback_fn = load_fn(libc_so, b"__system_property_read_callback\0")?;
-        let find_fn = load_fn(libc_so, b"__system_property_find\0")?;
-        Some(Implementation::New {
-            find_fn: mem::transmute(find_fn),
-            read_callback_fn: mem::transmute(read_callback_fn),
+impl std::fmt::Display for SetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            SetError::Unsupported => "setting system properties is not supported",
+            SetError::InvalidName => "invalid property name",
+            SetError::InvalidValue => "invalid property value",
+            SetError::SetFailed => "__system_property_set failed",
         })
     }
+}
 
-    unsafe fn load_old(libc_so: *mut c_void) -> Option<Implementation> {
-        let get_fn = load_fn(libc_so, b"__system_property_get\0")?;
-        Some(Implementation::Old {
-            get_fn: mem::transmute(get_fn),
-        })
-    }
+impl std::error::Error for SetError {}
 
-    unsafe fn new(libc_so: *mut c_void) -> Option<Self> {
-        Self::load_new(libc_so)
-            .or_else(|| Self::load_old(libc_so))
-    }
+/// An error returned by [`AndroidSystemProperties::try_get`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyError {
+    /// This platform is not Android, or `libc.so` could not be loaded, or none of the
+    /// supported `__system_property_*` symbols could be resolved.
+    Uninitialized,
+    /// The property does not exist (or could not be read).
+    PropertyAbsent,
+    /// `name` contains a NUL byte.
+    InvalidName,
+    /// The property value is not valid UTF-8.
+    InvalidUtf8,
+}
 
-    fn get(&self, cname: *const c_char) -> Option<String> {
-        match self {
-            Implementation::New { find_fn, read_callback_fn } => {
-                let info = unsafe { (find_fn)(cname) };
-
-                if info.is_null() {
-                    return None;
-                }
-
-                let mut result = String::new();
-
-                unsafe { (read_callback_fn)(info, property_callback, &mut result) };
-
-                Some(result)
-            }
-            Implementation::Old { get_fn } => {
-                // The constant is PROP_VALUE_MAX in Android's libc/include/sys/system_properties.h
-                const PROPERTY_VALUE_MAX: usize = 92;
-                let mut buffer: Vec<u8> = Vec::with_capacity(PROPERTY_VALUE_MAX);
-                let raw = buffer.as_mut_ptr().cast();
-
-                let len = unsafe { (get_fn)(cname, raw) };
-
-                if len > 0 {
-                    assert!(len as usize <= buffer.capacity());
-                    unsafe { buffer.set_len(len as usize); }
-                    String::from_utf8(buffer).ok()
-                } else {
-                    None
-                }
-            }
-        }
+impl std::fmt::Display for PropertyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            PropertyError::Uninitialized => "android system properties are not available",
+            PropertyError::PropertyAbsent => "property does not exist",
+            PropertyError::InvalidName => "invalid property name",
+            PropertyError::InvalidUtf8 => "property value is not valid UTF-8",
+        })
     }
 }
 
+impl std::error::Error for PropertyError {}
+
 #[derive(Debug)]
 /// An object that can retrieve android system properties.
 ///
@@ -153,9 +123,7 @@ impl Implementation {
 /// ```
 pub struct AndroidSystemProperties {
     #[cfg(target_os = "android")]
-    libc_so: *mut c_void,
-    #[cfg(target_os = "android")]
-    implementation: Option<Implementation>,
+    properties: Option<Properties>,
 }
 
 impl AndroidSystemProperties {
@@ -168,25 +136,14 @@ impl AndroidSystemProperties {
     #[cfg(target_os = "android")]
     /// Create an entry point for accessing Android properties.
     pub fn new() -> Self {
-        let libc_so = unsafe { libc::dlopen(b"libc.so\0".as_ptr().cast(), libc::RTLD_NOLOAD) };
-
-        let mut properties = AndroidSystemProperties {
-            libc_so,
-            implementation: None,
-        };
-
-        if libc_so.is_null() {
-            return properties;
+        AndroidSystemProperties {
+            properties: Properties::new(),
         }
-
-        properties.implementation = unsafe { Implementation::new(libc_so) };
-
-        properties
     }
 
     /// Retrieve a system property.
     ///
-    /// Returns None if the operation fails.
+    /// Returns None if the operation fails. Use [`Self::try_get`] to find out why.
     ///
     /// # Example
     ///
@@ -199,25 +156,174 @@ impl AndroidSystemProperties {
     /// }
     /// ```
     pub fn get(&self, name: &str) -> Option<String> {
+        self.try_get(name).ok()
+    }
+
+    /// Retrieve a system property, reporting why the operation failed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use android_system_properties::AndroidSystemProperties;
+    /// let properties = AndroidSystemProperties::new();
+    ///
+    /// match properties.try_get("persist.sys.timezone") {
+    ///     Ok(value) => println!("{}", value),
+    ///     Err(err) => eprintln!("could not read property: {}", err),
+    /// }
+    /// ```
+    pub fn try_get(&self, name: &str) -> Result<String, PropertyError> {
+        #[cfg(not(target_os = "android"))]
+        return Err((name, PropertyError::Uninitialized).1);
+
+        #[cfg(target_os = "android")]
+        return self.properties.as_ref().ok_or(PropertyError::Uninitialized)?.get(name);
+    }
+
+    /// Calls `f` once for every property this process is allowed to read.
+    ///
+    /// Does nothing on platforms other than Android, and on pre-Lollipop Android versions
+    /// where the underlying `__system_property_foreach` symbol is not available. Properties
+    /// whose name or value is not valid UTF-8 are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use android_system_properties::AndroidSystemProperties;
+    /// let properties = AndroidSystemProperties::new();
+    ///
+    /// properties.foreach(|name, value| {
+    ///     println!("{} = {}", name, value);
+    /// });
+    /// ```
+    pub fn foreach<F: FnMut(&str, &str)>(&self, f: F) {
+        #[cfg(not(target_os = "android"))]
+        let _ = f;
+
+        #[cfg(target_os = "android")]
+        if let Some(properties) = self.properties.as_ref() {
+            properties.foreach(f);
+        }
+    }
+
+    /// Set a system property.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use android_system_properties::AndroidSystemProperties;
+    /// let properties = AndroidSystemProperties::new();
+    ///
+    /// let _ = properties.set("debug.my-app.enabled", "1");
+    /// ```
+    pub fn set(&self, name: &str, value: &str) -> Result<(), SetError> {
+        if name.len() >= PROP_NAME_MAX {
+            return Err(SetError::InvalidName);
+        }
+        if value.len() >= PROP_VALUE_MAX {
+            return Err(SetError::InvalidValue);
+        }
+
+        #[cfg(not(target_os = "android"))]
+        return Err((name, value, SetError::Unsupported).2);
+
+        #[cfg(target_os = "android")]
+        return self.properties.as_ref().ok_or(SetError::Unsupported)?.set(name, value);
+    }
+
+    /// Returns a watcher that can block until `name` changes.
+    ///
+    /// Returns `None` on platforms other than Android, and on pre-Lollipop Android versions
+    /// where `__system_property_wait` is not available.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use android_system_properties::AndroidSystemProperties;
+    /// let properties = AndroidSystemProperties::new();
+    ///
+    /// if let Some(mut watcher) = properties.watch("persist.sys.timezone") {
+    ///     if let Some((value, _serial)) = watcher.wait() {
+    ///         println!("{}", value);
+    ///     }
+    /// }
+    /// ```
+    pub fn watch(&self, name: &str) -> Option<PropertyWatcher> {
         #[cfg(not(target_os = "android"))]
         return (name, None).1;
 
         #[cfg(target_os = "android")]
-        return {
-            let implementation = self.implementation.as_ref()?;
-            let cname = CString::new(name).ok()?;
-            implementation.get(cname.as_ptr().cast())
-        };
+        return Some(PropertyWatcher {
+            inner: self.properties.as_ref()?.watch(name)?,
+        });
     }
-}
 
-#[cfg(target_os = "android")]
-impl Drop for AndroidSystemProperties {
-    fn drop(&mut self) {
-        if !self.libc_so.is_null() {
-            unsafe {
-                libc::dlclose(self.libc_so);
-            }
+    /// Retrieve a system property and parse it as a boolean, using the same conventions as
+    /// Android's sysprop-generated accessors: `"1"`, `"true"`, `"y"`, `"yes"` and `"on"`
+    /// (case-insensitive) are `true`; `"0"`, `"false"`, `"n"`, `"no"` and `"off"` are `false`.
+    /// Returns `None` if the property is absent or its value matches neither list.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use android_system_properties::AndroidSystemProperties;
+    /// let properties = AndroidSystemProperties::new();
+    ///
+    /// if let Some(enabled) = properties.get_bool("ro.debuggable") {
+    ///     println!("{}", enabled);
+    /// }
+    /// ```
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)?.to_ascii_lowercase().as_str() {
+            "1" | "true" | "y" | "yes" | "on" => Some(true),
+            "0" | "false" | "n" | "no" | "off" => Some(false),
+            _ => None,
         }
     }
+
+    /// Retrieve a system property and parse it as `T`, e.g. an integer or float.
+    ///
+    /// Returns `None` if the property is absent or fails to parse.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use android_system_properties::AndroidSystemProperties;
+    /// let properties = AndroidSystemProperties::new();
+    ///
+    /// let retries: Option<u32> = properties.get_parsed("debug.my-app.retries");
+    /// ```
+    pub fn get_parsed<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.parse().ok()
+    }
+}
+
+/// A handle on a single property, used to block until it changes.
+///
+/// Returned by [`AndroidSystemProperties::watch`].
+#[derive(Debug)]
+pub struct PropertyWatcher {
+    #[cfg(target_os = "android")]
+    inner: android::PropertyWatcher,
+}
+
+impl PropertyWatcher {
+    /// Blocks until the watched property changes, then returns its new value and serial.
+    /// Returns `None` if the wait fails, or if the new value is not valid UTF-8.
+    pub fn wait(&mut self) -> Option<(String, u32)> {
+        #[cfg(not(target_os = "android"))]
+        return None;
+
+        #[cfg(target_os = "android")]
+        return self.inner.wait(None);
+    }
+
+    /// Like [`Self::wait`], but gives up and returns `None` once `timeout` elapses.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<(String, u32)> {
+        #[cfg(not(target_os = "android"))]
+        return (timeout, None).1;
+
+        #[cfg(target_os = "android")]
+        return self.inner.wait(Some(timeout));
+    }
 }